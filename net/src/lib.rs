@@ -6,6 +6,7 @@ use std::fmt;
 use std::io;
 use std::net::SocketAddr;
 use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 
 use thiserror::Error;
 
@@ -18,8 +19,31 @@ mod id;
 // a "util" crate or similar.
 pub use id::Id;
 
+#[cfg(feature = "qlog")]
+mod qlog;
+#[cfg(feature = "qlog")]
+pub use qlog::{set_qlog_sink, PacketKind, QlogEvent, QlogSink};
+
 pub const DATAGRAM_MTU: usize = 1500;
 
+/// ECN codepoint carried in the IP header of a datagram.
+///
+/// These are the four values of the two-bit ECN field (RFC 3168), read from
+/// `IP_TOS`/`IPV6_TCLASS` on receive and set via the same mechanism on send.
+/// Carrying this up from the socket layer lets congestion control react to a
+/// `Ce` mark before packet loss is even observed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcnCodepoint {
+    /// Not ECN-Capable Transport.
+    NotEct,
+    /// ECN-Capable Transport, codepoint 0.
+    Ect0,
+    /// ECN-Capable Transport, codepoint 1.
+    Ect1,
+    /// Congestion Experienced.
+    Ce,
+}
+
 #[derive(Debug, Error)]
 pub enum NetError {
     #[error("{0}")]
@@ -30,25 +54,191 @@ pub enum NetError {
 }
 
 /// An outgoing packet
+///
+/// Fields are private and only reachable through [`Transmit::new`] and the
+/// accessor methods below, so that every `Transmit` gets the qlog
+/// instrumentation `new` performs — a struct literal would silently bypass it.
 pub struct Transmit {
     /// The source socket this packet should be sent from.
     ///
     /// For ICE it's important to match up outgoing packets with source network interface.
-    pub source: SocketAddr,
+    source: SocketAddr,
 
     /// This socket this datagram should be sent to.
-    pub destination: SocketAddr,
+    destination: SocketAddr,
 
     /// Contents of the datagram.
-    pub contents: DatagramSend,
+    ///
+    /// When `segment_size` is set, this holds multiple datagrams concatenated
+    /// back-to-back, each `segment_size` bytes long except possibly the last.
+    contents: DatagramSend,
+
+    /// Size of each segment in `contents`, if it holds more than one datagram.
+    ///
+    /// `None` means `contents` is a single datagram and should be sent with one
+    /// `sendmsg` call. `Some(n)` means `contents` is a batch of datagrams of `n`
+    /// bytes each (the final one may be shorter), to be sent with a single
+    /// `sendmsg` using `UDP_SEGMENT` (GSO). A socket layer without GSO support
+    /// can always fall back to splitting on `segment_size` and sending each
+    /// chunk separately.
+    segment_size: Option<usize>,
+
+    /// The ECN codepoint to set on the IP header when sending, if any.
+    ecn: Option<EcnCodepoint>,
+}
+
+impl Transmit {
+    /// Creates a new `Transmit`.
+    ///
+    /// When the `qlog` feature is enabled this also emits one qlog packet
+    /// event per datagram in `contents` (per `segment_size`-sized chunk when
+    /// this is a GSO batch). A datagram that `DatagramRecv::try_from` does not
+    /// accept produces no event.
+    pub fn new(
+        source: SocketAddr,
+        destination: SocketAddr,
+        contents: DatagramSend,
+        segment_size: Option<usize>,
+        ecn: Option<EcnCodepoint>,
+    ) -> Self {
+        #[cfg(feature = "qlog")]
+        {
+            let chunk_size = segment_size
+                .filter(|&n| n > 0)
+                .unwrap_or_else(|| contents.len().max(1));
+
+            for chunk in contents.chunks(chunk_size) {
+                if let Ok(kind) = DatagramRecv::try_from(chunk).map(|c| c.qlog_kind()) {
+                    qlog::emit(qlog::QlogEvent::Packet {
+                        kind,
+                        len: chunk.len(),
+                        source,
+                        destination,
+                        at: std::time::SystemTime::now(),
+                    });
+                }
+            }
+        }
+
+        Transmit {
+            source,
+            destination,
+            contents,
+            segment_size,
+            ecn,
+        }
+    }
+
+    /// The source socket this packet should be sent from.
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+
+    /// This socket this datagram should be sent to.
+    pub fn destination(&self) -> SocketAddr {
+        self.destination
+    }
+
+    /// Contents of the datagram.
+    pub fn contents(&self) -> &DatagramSend {
+        &self.contents
+    }
+
+    /// Size of each segment in `contents`, if it holds more than one datagram.
+    pub fn segment_size(&self) -> Option<usize> {
+        self.segment_size
+    }
+
+    /// The ECN codepoint to set on the IP header when sending, if any.
+    pub fn ecn(&self) -> Option<EcnCodepoint> {
+        self.ecn
+    }
+}
+
+/// A reusable pool of buffers for outgoing datagrams.
+///
+/// Sustained RTP sending otherwise allocates a fresh `Vec<u8>` per packet.
+/// Checking a buffer out with `DatagramSend::from_pool` and letting it drop
+/// back into the pool once the `Transmit` is consumed keeps steady-state
+/// sending allocation-free.
+#[derive(Debug, Clone)]
+pub struct Pool(Arc<Mutex<Vec<Vec<u8>>>>);
+
+impl Pool {
+    pub fn new() -> Self {
+        Pool(Arc::new(Mutex::new(Vec::new())))
+    }
+
+    fn take(&self) -> Vec<u8> {
+        self.0
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(DATAGRAM_MTU))
+    }
+
+    fn recycle(&self, mut buf: Vec<u8>) {
+        // A GSO batch (chunk0-1) can grow a checked-out buffer to many times
+        // `DATAGRAM_MTU`. Drop those instead of requeuing them, so the pool
+        // doesn't end up permanently handing oversized buffers to ordinary
+        // single-packet sends.
+        if buf.capacity() > DATAGRAM_MTU {
+            return;
+        }
+        buf.clear();
+        self.0.lock().unwrap().push(buf);
+    }
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+impl Pool {
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
 }
 
 #[derive(Debug)]
-pub struct DatagramSend(Vec<u8>);
+pub struct DatagramSend {
+    buf: Vec<u8>,
+    pool: Option<Pool>,
+}
 
 impl DatagramSend {
     pub fn new(v: Vec<u8>) -> Self {
-        DatagramSend(v)
+        DatagramSend {
+            buf: v,
+            pool: None,
+        }
+    }
+
+    /// Checks a capacity-`DATAGRAM_MTU` buffer out of `pool`. The buffer is
+    /// returned to `pool` automatically when this value is dropped, so
+    /// steady-state sending from a `Pool` performs no heap allocation.
+    pub fn from_pool(pool: &Pool) -> Self {
+        DatagramSend {
+            buf: pool.take(),
+            pool: Some(pool.clone()),
+        }
+    }
+
+    /// Appends `data`, growing the buffer. Used to fill a buffer checked out
+    /// via `from_pool`, which starts out empty.
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+}
+
+impl Drop for DatagramSend {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.recycle(std::mem::take(&mut self.buf));
+        }
     }
 }
 
@@ -61,7 +251,10 @@ pub struct Receive<'a> {
     /// The destination ip of the datagram.
     pub destination: SocketAddr,
 
-    /// Parsed contents of the datagram.    
+    /// The ECN codepoint read off the IP header of the datagram, if known.
+    pub ecn: Option<EcnCodepoint>,
+
+    /// Parsed contents of the datagram.
     pub contents: DatagramRecv<'a>,
 }
 
@@ -70,15 +263,67 @@ impl<'a> Receive<'a> {
     pub fn new(
         source: SocketAddr,
         destination: SocketAddr,
+        ecn: Option<EcnCodepoint>,
         buf: &'a [u8],
     ) -> Result<Self, NetError> {
         let contents = DatagramRecv::try_from(buf)?;
+
+        #[cfg(feature = "qlog")]
+        qlog::emit(qlog::QlogEvent::Packet {
+            kind: contents.qlog_kind(),
+            len: buf.len(),
+            source,
+            destination,
+            at: std::time::SystemTime::now(),
+        });
+
         Ok(Receive {
             source,
             destination,
+            ecn,
             contents,
         })
     }
+
+    /// Splits a coalesced GRO buffer into the individual datagrams it contains.
+    ///
+    /// `buf` is one `recvmsg` read that the OS coalesced under `UDP_GRO`, and
+    /// `segment_size` is the segment size it reported alongside it. Every segment
+    /// is `segment_size` bytes except possibly the last, which may be shorter. Each
+    /// segment is parsed independently via `DatagramRecv::try_from`, so a sender
+    /// behind GSO can be handled exactly like one making a `sendmsg` call per packet.
+    pub fn split(
+        source: SocketAddr,
+        destination: SocketAddr,
+        ecn: Option<EcnCodepoint>,
+        buf: &'a [u8],
+        segment_size: usize,
+    ) -> impl Iterator<Item = Result<Self, NetError>> {
+        // `slice::chunks` panics if the chunk size is 0. A segment size of 0
+        // can only mean a bad or racy GRO report from the OS, so treat it as
+        // "nothing to split" rather than letting the caller crash on it.
+        let buf = if segment_size == 0 { &[] } else { buf };
+
+        buf.chunks(segment_size.max(1)).map(move |chunk| {
+            let contents = DatagramRecv::try_from(chunk)?;
+
+            #[cfg(feature = "qlog")]
+            qlog::emit(qlog::QlogEvent::Packet {
+                kind: contents.qlog_kind(),
+                len: chunk.len(),
+                source,
+                destination,
+                at: std::time::SystemTime::now(),
+            });
+
+            Ok(Receive {
+                source,
+                destination,
+                ecn,
+                contents,
+            })
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -87,6 +332,22 @@ pub enum DatagramRecv<'a> {
     Dtls(&'a [u8]),
     Rtp(&'a [u8]),
     Rtcp(&'a [u8]),
+    /// A TURN ChannelData message (RFC 8656 section 12.4), de-encapsulated into
+    /// its channel number and inner payload.
+    ChannelData { channel: u16, data: &'a [u8] },
+}
+
+#[cfg(feature = "qlog")]
+impl<'a> DatagramRecv<'a> {
+    fn qlog_kind(&self) -> qlog::PacketKind {
+        match self {
+            DatagramRecv::Stun(_) => qlog::PacketKind::Stun,
+            DatagramRecv::Dtls(_) => qlog::PacketKind::Dtls,
+            DatagramRecv::Rtp(_) => qlog::PacketKind::Rtp,
+            DatagramRecv::Rtcp(_) => qlog::PacketKind::Rtcp,
+            DatagramRecv::ChannelData { .. } => qlog::PacketKind::ChannelData,
+        }
+    }
 }
 
 impl<'a> TryFrom<&'a [u8]> for DatagramRecv<'a> {
@@ -102,6 +363,19 @@ impl<'a> TryFrom<&'a [u8]> for DatagramRecv<'a> {
             MultiplexKind::Dtls => Dtls(value),
             MultiplexKind::Rtp => Rtp(value),
             MultiplexKind::Rtcp => Rtcp(value),
+            MultiplexKind::ChannelData => {
+                let channel = u16::from_be_bytes([value[0], value[1]]);
+                let len = u16::from_be_bytes([value[2], value[3]]) as usize;
+
+                let data = value[4..].get(..len).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "ChannelData length exceeds buffer",
+                    )
+                })?;
+
+                ChannelData { channel, data }
+            }
         })
     }
 }
@@ -112,6 +386,7 @@ pub(crate) enum MultiplexKind {
     Dtls,
     Rtp,
     Rtcp,
+    ChannelData,
 }
 
 impl<'a> TryFrom<&'a [u8]> for MultiplexKind {
@@ -125,6 +400,10 @@ impl<'a> TryFrom<&'a [u8]> for MultiplexKind {
             Ok(MultiplexKind::Stun)
         } else if byte0 >= 20 && byte0 < 64 {
             Ok(MultiplexKind::Dtls)
+        } else if (0x40..0x80).contains(&byte0) && len >= 4 {
+            // TURN ChannelData (RFC 8656): a 2-byte channel number in
+            // 0x4000-0x7FFF followed by a 2-byte length and the payload.
+            Ok(MultiplexKind::ChannelData)
         } else if byte0 >= 128 && byte0 < 192 && len > 2 {
             let byte1 = value[1];
             let payload_type = byte1 & 0x7f;
@@ -156,6 +435,7 @@ impl<'a> TryFrom<&'a Transmit> for Receive<'a> {
         Ok(Receive {
             source: t.source,
             destination: t.destination,
+            ecn: t.ecn,
             contents: DatagramRecv::try_from(&t.contents[..])?,
         })
     }
@@ -167,6 +447,8 @@ impl fmt::Debug for Transmit {
             .field("source", &self.source)
             .field("destination", &self.destination)
             .field("len", &self.contents.len())
+            .field("segment_size", &self.segment_size)
+            .field("ecn", &self.ecn)
             .finish()
     }
 }
@@ -175,6 +457,117 @@ impl Deref for DatagramSend {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_data_exact_fit() {
+        let buf = [0x40, 0x01, 0x00, 0x03, b'a', b'b', b'c'];
+        match DatagramRecv::try_from(&buf[..]).unwrap() {
+            DatagramRecv::ChannelData { channel, data } => {
+                assert_eq!(channel, 0x4001);
+                assert_eq!(data, b"abc");
+            }
+            other => panic!("expected ChannelData, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn channel_data_zero_length_payload() {
+        let buf = [0x40, 0x01, 0x00, 0x00];
+        match DatagramRecv::try_from(&buf[..]).unwrap() {
+            DatagramRecv::ChannelData { channel, data } => {
+                assert_eq!(channel, 0x4001);
+                assert!(data.is_empty());
+            }
+            other => panic!("expected ChannelData, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn channel_data_length_exceeding_buffer_is_an_error() {
+        // Declares 0xff (255) bytes of payload, but the buffer holds none.
+        let buf = [0x40, 0x01, 0x00, 0xff];
+        let err = DatagramRecv::try_from(&buf[..]).unwrap_err();
+        assert!(matches!(err, NetError::Io(_)));
+    }
+
+    #[test]
+    fn multiplex_kind_recognizes_channel_data_range() {
+        let buf = [0x7f, 0xff, 0x00, 0x00];
+        assert_eq!(
+            MultiplexKind::try_from(&buf[..]).unwrap(),
+            MultiplexKind::ChannelData
+        );
+    }
+
+    fn test_addr() -> SocketAddr {
+        "127.0.0.1:0".parse().unwrap()
+    }
+
+    fn dtls_len(contents: &DatagramRecv) -> usize {
+        match contents {
+            DatagramRecv::Dtls(data) => data.len(),
+            other => panic!("expected Dtls, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn split_yields_full_segments_and_a_shorter_remainder() {
+        // byte0 in 20..64 is always classified Dtls regardless of length.
+        let buf = [30u8; 10];
+        let addr = test_addr();
+
+        let received: Vec<_> = Receive::split(addr, addr, None, &buf, 4)
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(received.len(), 3);
+        assert_eq!(dtls_len(&received[0].contents), 4);
+        assert_eq!(dtls_len(&received[1].contents), 4);
+        assert_eq!(dtls_len(&received[2].contents), 2);
+    }
+
+    #[test]
+    fn split_with_zero_segment_size_yields_nothing() {
+        let buf = [30u8; 10];
+        let addr = test_addr();
+
+        let received: Vec<_> = Receive::split(addr, addr, None, &buf, 0).collect();
+
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn from_pool_reuses_the_buffer_after_drop() {
+        let pool = Pool::new();
+
+        let mut first = DatagramSend::from_pool(&pool);
+        first.extend_from_slice(b"hello");
+        let ptr = first.as_ptr();
+        drop(first);
+
+        assert_eq!(pool.len(), 1);
+
+        let second = DatagramSend::from_pool(&pool);
+        assert_eq!(pool.len(), 0);
+        assert!(second.is_empty());
+        assert_eq!(second.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn oversized_buffer_is_dropped_instead_of_recycled() {
+        let pool = Pool::new();
+
+        let mut buf = DatagramSend::from_pool(&pool);
+        buf.extend_from_slice(&[0u8; DATAGRAM_MTU * 3]);
+        drop(buf);
+
+        assert_eq!(pool.len(), 0);
     }
 }
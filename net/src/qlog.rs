@@ -0,0 +1,110 @@
+//! Structured event export for the packet demux path.
+//!
+//! Modelled on the qlog event streams neqo and tquic produce, so existing
+//! qvis-style tooling can ingest the same shape of events. Nothing here does
+//! anything unless a sink is installed with [`set_qlog_sink`]; with no sink
+//! installed, [`emit`] is a no-op, so there's zero cost when this feature
+//! goes unused.
+//!
+//! TODO(h1t/str0m#chunk0-4): this only covers the packet-demux half of that
+//! request. It also asked for dedicated STUN transaction events (request
+//! sent, response matched, retransmit, timeout) keyed on `TransId`, emitted
+//! from the STUN transaction-tracking code. That code (`stun.rs`) isn't part
+//! of this tree, so there's nowhere to wire that emission up yet — it's
+//! deliberately deferred, not forgotten. Land `QlogEvent::Stun`/`StunEvent`
+//! and the corresponding `emit()` calls alongside the real retransmission
+//! state machine when it's available.
+
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Classification of a packet as it crosses the demux path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    Stun,
+    Dtls,
+    Rtp,
+    Rtcp,
+    ChannelData,
+}
+
+impl PacketKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PacketKind::Stun => "stun",
+            PacketKind::Dtls => "dtls",
+            PacketKind::Rtp => "rtp",
+            PacketKind::Rtcp => "rtcp",
+            PacketKind::ChannelData => "channel_data",
+        }
+    }
+}
+
+/// A single qlog-style event emitted while demuxing a packet.
+#[derive(Debug, Clone)]
+pub enum QlogEvent {
+    Packet {
+        kind: PacketKind,
+        len: usize,
+        source: SocketAddr,
+        destination: SocketAddr,
+        at: SystemTime,
+    },
+}
+
+impl QlogEvent {
+    /// Renders the event as a single-line JSON object, in the shape of a
+    /// qlog/qvis event record.
+    pub fn to_json(&self) -> String {
+        match self {
+            QlogEvent::Packet {
+                kind,
+                len,
+                source,
+                destination,
+                at,
+            } => format!(
+                "{{\"time\":{},\"name\":\"demux:packet\",\"data\":{{\"kind\":\"{}\",\"len\":{},\"source\":\"{}\",\"destination\":\"{}\"}}}}",
+                epoch_millis(*at),
+                kind.as_str(),
+                len,
+                source,
+                destination,
+            ),
+        }
+    }
+}
+
+fn epoch_millis(at: SystemTime) -> u128 {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+/// Receives qlog events. Install one with [`set_qlog_sink`] to get
+/// packet-level visibility into multiplexing and ICE connectivity checks
+/// without wiresharking DTLS-encrypted flows.
+pub trait QlogSink: Send + Sync {
+    fn on_event(&self, event: &QlogEvent);
+}
+
+struct NoopSink;
+
+impl QlogSink for NoopSink {
+    fn on_event(&self, _event: &QlogEvent) {}
+}
+
+static SINK: OnceLock<Box<dyn QlogSink>> = OnceLock::new();
+
+/// Installs the sink that receives qlog events for the lifetime of the
+/// process. Can only be set once: if a sink (including the implicit no-op
+/// default installed by the first call to [`emit`]) is already installed,
+/// this returns `false` and the passed-in `sink` is dropped without being
+/// used, so a caller can tell wiring it up failed instead of silently
+/// getting a no-op logger.
+pub fn set_qlog_sink(sink: Box<dyn QlogSink>) -> bool {
+    SINK.set(sink).is_ok()
+}
+
+pub(crate) fn emit(event: QlogEvent) {
+    SINK.get_or_init(|| Box::new(NoopSink)).on_event(&event);
+}